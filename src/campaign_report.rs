@@ -0,0 +1,76 @@
+// Copyright 2021, Console Ltd https://console.dev
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+// "How did the last newsletter do" reporting.
+//
+// `Lists::get_list_info` only exposes aggregate list stats. This module
+// looks up the most recently sent campaign for the configured list and
+// pulls its performance via the Mailchimp Reports API.
+
+use crate::config::Config;
+use mailchimp::{Campaigns, MailchimpApi, Reports};
+use std::collections::HashMap;
+
+/// Performance data for a single sent campaign.
+#[derive(Debug, Clone)]
+pub struct CampaignReport {
+    pub subject: String,
+    pub send_time: String,
+    pub opens: Option<f64>,
+    pub unique_opens: Option<f64>,
+    pub open_rate: Option<f64>,
+    pub clicks: Option<f64>,
+    pub click_rate: Option<f64>,
+    pub bounces: Option<f64>,
+    pub unsubscribes: Option<f64>,
+}
+
+/// Look up the most recently sent campaign for `config.mailchimp_list_id`
+/// and fetch its report.
+pub fn fetch_latest(config: &Config) -> Result<CampaignReport, String> {
+    let api = MailchimpApi::new(&config.mailchimp_apikey);
+
+    // Find the most recently sent campaign for this list
+    let campaigns = Campaigns::new(api.clone());
+
+    let mut filters = HashMap::new();
+    filters.insert("list_id".to_string(), config.mailchimp_list_id.clone());
+    filters.insert("status".to_string(), "sent".to_string());
+    filters.insert("sort_field".to_string(), "send_time".to_string());
+    filters.insert("sort_dir".to_string(), "DESC".to_string());
+    filters.insert("count".to_string(), "1".to_string());
+
+    let campaign_list = campaigns
+        .get_campaigns(filters)
+        .map_err(|e| format!("Error listing Mailchimp campaigns: {:?}", e))?;
+
+    let campaign = campaign_list
+        .campaigns
+        .first()
+        .ok_or_else(|| String::from("No sent campaigns found"))?;
+
+    // Pull its report
+    let reports = Reports::new(api);
+    let report = reports
+        .get_report(&campaign.id, HashMap::new())
+        .map_err(|e| format!("Error getting Mailchimp campaign report: {:?}", e))?;
+
+    // Only report no bounce total if neither half is known; a missing
+    // hard (or soft) count alone shouldn't zero out the other half.
+    let bounces = match (report.bounces.hard_bounces, report.bounces.soft_bounces) {
+        (None, None) => None,
+        (hard, soft) => Some(hard.unwrap_or(0.0) + soft.unwrap_or(0.0)),
+    };
+
+    Ok(CampaignReport {
+        subject: campaign.settings.subject_line.clone(),
+        send_time: campaign.send_time.clone(),
+        opens: report.opens.opens_total,
+        unique_opens: report.opens.unique_opens,
+        open_rate: report.opens.open_rate,
+        clicks: report.clicks.clicks_total,
+        click_rate: report.clicks.click_rate,
+        bounces,
+        unsubscribes: report.unsubscribed,
+    })
+}
@@ -0,0 +1,297 @@
+// Copyright 2021, Console Ltd https://console.dev
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+// Validated configuration.
+//
+// Previously every handler read the environment directly with
+// `env::var(...).expect(...)`, so a single missing variable would panic
+// mid-request instead of failing at startup. `Config` is built once in
+// `rocket()`, validates every field up front, and is handed to handlers
+// as Rocket managed state.
+
+use std::env;
+use std::fmt;
+use std::fs;
+
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 300;
+const DEFAULT_DB_PATH: &str = "totorobot.db";
+const DEFAULT_NOTIFIER: &str = "basecamp";
+
+// Mirrors `Config`, but every field is an optional raw string so we can
+// merge values from the environment and an optional TOML file before
+// parsing and validating. Fields are read one at a time (rather than
+// deserialized as a whole struct) so a single malformed value can't
+// throw away every other correctly-set field.
+#[derive(Debug, Default)]
+struct RawConfig {
+    mailchimp_apikey: Option<String>,
+    mailchimp_list_id: Option<String>,
+    notifier: Option<String>,
+    basecamp_boturl: Option<String>,
+    slack_webhook_url: Option<String>,
+    discord_webhook_url: Option<String>,
+    production: Option<String>,
+    poll_interval: Option<String>,
+    db_path: Option<String>,
+}
+
+impl RawConfig {
+    fn from_env() -> RawConfig {
+        RawConfig {
+            mailchimp_apikey: env::var("TOTORO_MAILCHIMP_APIKEY").ok(),
+            mailchimp_list_id: env::var("TOTORO_MAILCHIMP_LIST_ID").ok(),
+            notifier: env::var("TOTORO_NOTIFIER").ok(),
+            basecamp_boturl: env::var("TOTORO_BASECAMP_BOTURL").ok(),
+            slack_webhook_url: env::var("TOTORO_SLACK_WEBHOOK_URL").ok(),
+            discord_webhook_url: env::var("TOTORO_DISCORD_WEBHOOK_URL").ok(),
+            production: env::var("TOTORO_PRODUCTION").ok(),
+            poll_interval: env::var("TOTORO_POLL_INTERVAL").ok(),
+            db_path: env::var("TOTORO_DB_PATH").ok(),
+        }
+    }
+
+    // Parse a TOML file's keys individually, the same way `from_env`
+    // reads each variable individually, so one malformed key doesn't
+    // discard the rest of the file the way a single
+    // `toml::from_str::<RawConfig>()` call would.
+    fn from_toml(contents: &str, problems: &mut Vec<String>) -> RawConfig {
+        let table: toml::Value = match contents.parse() {
+            Ok(value) => value,
+            Err(e) => {
+                problems.push(format!("TOTORO_CONFIG_PATH is not valid TOML: {}", e));
+                return RawConfig::default();
+            }
+        };
+
+        fn field(table: &toml::Value, name: &str, problems: &mut Vec<String>) -> Option<String> {
+            match table.get(name) {
+                None => None,
+                Some(toml::Value::String(s)) => Some(s.clone()),
+                Some(toml::Value::Integer(i)) => Some(i.to_string()),
+                Some(toml::Value::Boolean(b)) => Some(b.to_string()),
+                Some(other) => {
+                    problems.push(format!(
+                        "TOTORO_CONFIG_PATH field {:?} has an unsupported type: {:?}",
+                        name, other
+                    ));
+                    None
+                }
+            }
+        }
+
+        RawConfig {
+            mailchimp_apikey: field(&table, "mailchimp_apikey", problems),
+            mailchimp_list_id: field(&table, "mailchimp_list_id", problems),
+            notifier: field(&table, "notifier", problems),
+            basecamp_boturl: field(&table, "basecamp_boturl", problems),
+            slack_webhook_url: field(&table, "slack_webhook_url", problems),
+            discord_webhook_url: field(&table, "discord_webhook_url", problems),
+            production: field(&table, "production", problems),
+            poll_interval: field(&table, "poll_interval", problems),
+            db_path: field(&table, "db_path", problems),
+        }
+    }
+
+    // Fill in any fields that are `None` with `other`'s value.
+    fn merge(mut self, other: RawConfig) -> RawConfig {
+        self.mailchimp_apikey = self.mailchimp_apikey.or(other.mailchimp_apikey);
+        self.mailchimp_list_id = self.mailchimp_list_id.or(other.mailchimp_list_id);
+        self.notifier = self.notifier.or(other.notifier);
+        self.basecamp_boturl = self.basecamp_boturl.or(other.basecamp_boturl);
+        self.slack_webhook_url = self.slack_webhook_url.or(other.slack_webhook_url);
+        self.discord_webhook_url = self.discord_webhook_url.or(other.discord_webhook_url);
+        self.production = self.production.or(other.production);
+        self.poll_interval = self.poll_interval.or(other.poll_interval);
+        self.db_path = self.db_path.or(other.db_path);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mailchimp_apikey: String,
+    pub mailchimp_list_id: String,
+    pub notifiers: Vec<String>,
+    pub basecamp_boturl: Option<String>,
+    pub slack_webhook_url: Option<String>,
+    pub discord_webhook_url: Option<String>,
+    pub production: bool,
+    pub port: u16,
+    pub poll_interval: u64,
+    pub db_path: String,
+}
+
+/// All the problems found while validating a `Config`, collected so they
+/// can be reported together instead of one panic at a time.
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Accepts the usual boolean spellings plus "1"/"0", since existing
+// deployments set `TOTORO_PRODUCTION=1` rather than `=true`.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+impl Config {
+    /// Build and validate the config from `TOTORO_*` environment
+    /// variables, falling back to a TOML file at `TOTORO_CONFIG_PATH`
+    /// (if set) for anything the environment doesn't provide. Azure's
+    /// `FUNCTIONS_CUSTOMHANDLER_PORT` is read separately since it isn't
+    /// one of our `TOTORO_*` variables.
+    pub fn load() -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+
+        let from_env = RawConfig::from_env();
+
+        let from_file = match env::var("TOTORO_CONFIG_PATH") {
+            Ok(path) => match fs::read_to_string(&path) {
+                Ok(contents) => RawConfig::from_toml(&contents, &mut problems),
+                Err(e) => {
+                    problems.push(format!(
+                        "TOTORO_CONFIG_PATH {:?} could not be read: {}",
+                        path, e
+                    ));
+                    RawConfig::default()
+                }
+            },
+            Err(_) => RawConfig::default(),
+        };
+
+        let raw = from_env.merge(from_file);
+
+        if raw.mailchimp_apikey.is_none() {
+            problems.push(String::from("TOTORO_MAILCHIMP_APIKEY is not set"));
+        }
+        if raw.mailchimp_list_id.is_none() {
+            problems.push(String::from("TOTORO_MAILCHIMP_LIST_ID is not set"));
+        }
+
+        let notifiers: Vec<String> = raw
+            .notifier
+            .as_deref()
+            .unwrap_or(DEFAULT_NOTIFIER)
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        for target in &notifiers {
+            match target.as_str() {
+                "basecamp" if raw.basecamp_boturl.is_none() => {
+                    problems.push(String::from(
+                        "TOTORO_NOTIFIER includes \"basecamp\" but TOTORO_BASECAMP_BOTURL is not set",
+                    ));
+                }
+                "slack" if raw.slack_webhook_url.is_none() => {
+                    problems.push(String::from(
+                        "TOTORO_NOTIFIER includes \"slack\" but TOTORO_SLACK_WEBHOOK_URL is not set",
+                    ));
+                }
+                "discord" if raw.discord_webhook_url.is_none() => {
+                    problems.push(String::from(
+                        "TOTORO_NOTIFIER includes \"discord\" but TOTORO_DISCORD_WEBHOOK_URL is not set",
+                    ));
+                }
+                "basecamp" | "slack" | "discord" => {}
+                other => {
+                    problems.push(format!("TOTORO_NOTIFIER has unknown target {:?}", other));
+                }
+            }
+        }
+
+        let port = match env::var("FUNCTIONS_CUSTOMHANDLER_PORT") {
+            Ok(val) => match val.parse() {
+                Ok(port) => port,
+                Err(_) => {
+                    problems.push(format!(
+                        "FUNCTIONS_CUSTOMHANDLER_PORT {:?} is not a valid port number",
+                        val
+                    ));
+                    DEFAULT_PORT
+                }
+            },
+            Err(_) => DEFAULT_PORT,
+        };
+
+        let production = match raw.production.as_deref() {
+            None => false,
+            Some(val) => match parse_bool(val) {
+                Some(b) => b,
+                None => {
+                    problems.push(format!(
+                        "TOTORO_PRODUCTION {:?} is not a valid boolean",
+                        val
+                    ));
+                    false
+                }
+            },
+        };
+
+        let poll_interval = match raw.poll_interval.as_deref() {
+            None => DEFAULT_POLL_INTERVAL_SECS,
+            Some(val) => match val.parse() {
+                Ok(secs) => secs,
+                Err(_) => {
+                    problems.push(format!(
+                        "TOTORO_POLL_INTERVAL {:?} is not a valid number of seconds",
+                        val
+                    ));
+                    DEFAULT_POLL_INTERVAL_SECS
+                }
+            },
+        };
+
+        if !problems.is_empty() {
+            return Err(ConfigError(problems));
+        }
+
+        Ok(Config {
+            mailchimp_apikey: raw.mailchimp_apikey.unwrap(),
+            mailchimp_list_id: raw.mailchimp_list_id.unwrap(),
+            notifiers,
+            basecamp_boturl: raw.basecamp_boturl,
+            slack_webhook_url: raw.slack_webhook_url,
+            discord_webhook_url: raw.discord_webhook_url,
+            production,
+            port,
+            poll_interval,
+            db_path: raw.db_path.unwrap_or_else(|| DEFAULT_DB_PATH.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_aggregates_missing_required_fields() {
+        // Config::load() reads process-wide env vars, so clear the ones
+        // this test cares about rather than assuming a clean process.
+        std::env::remove_var("TOTORO_MAILCHIMP_APIKEY");
+        std::env::remove_var("TOTORO_MAILCHIMP_LIST_ID");
+        std::env::remove_var("TOTORO_CONFIG_PATH");
+
+        let err = Config::load().expect_err("missing required fields should fail validation");
+        let message = err.to_string();
+        assert!(message.contains("TOTORO_MAILCHIMP_APIKEY is not set"));
+        assert!(message.contains("TOTORO_MAILCHIMP_LIST_ID is not set"));
+    }
+}
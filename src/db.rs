@@ -0,0 +1,233 @@
+// Copyright 2021, Console Ltd https://console.dev
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+// SQLite persistence of stats history.
+//
+// Every successful poll is appended as a row so we can look back and
+// report week-over-week deltas alongside the absolute numbers, instead
+// of just a one-shot snapshot.
+
+use crate::stats::CachedStats;
+use log::error;
+use once_cell::sync::OnceCell;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::Mutex;
+
+const ONE_WEEK_SECS: i64 = 7 * 24 * 60 * 60;
+
+static CONN: OnceCell<Mutex<Connection>> = OnceCell::new();
+
+fn create_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS stats_history (
+            id                              INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp                       INTEGER NOT NULL,
+            member_count                    REAL,
+            member_count_since_send         REAL,
+            unsubscribe_count_since_send    REAL,
+            avg_sub_rate                    REAL,
+            avg_unsub_rate                  REAL,
+            click_rate                      REAL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Open the database at `db_path` and create the schema if it doesn't
+/// already exist. Must be called once during startup, before `record`
+/// or `week_over_week` are used.
+pub fn init(db_path: &str) {
+    let conn = match Connection::open(db_path) {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Error opening stats history database: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = create_schema(&conn) {
+        error!("Error creating stats_history schema: {}", e);
+        return;
+    }
+
+    if CONN.set(Mutex::new(conn)).is_err() {
+        error!("db::init called more than once");
+    }
+}
+
+fn record_with(conn: &Connection, stats: &CachedStats) {
+    let result = conn.execute(
+        "INSERT INTO stats_history (
+            timestamp, member_count, member_count_since_send,
+            unsubscribe_count_since_send, avg_sub_rate, avg_unsub_rate, click_rate
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            stats.updated_at as i64,
+            stats.member_count,
+            stats.member_count_since_send,
+            stats.unsubscribe_count_since_send,
+            stats.avg_sub_rate,
+            stats.avg_unsub_rate,
+            stats.click_rate,
+        ],
+    );
+
+    if let Err(e) = result {
+        error!("Error recording stats history: {}", e);
+    }
+}
+
+/// Append a row recording this poll's stats.
+pub fn record(stats: &CachedStats) {
+    let conn = match CONN.get() {
+        Some(conn) => conn,
+        None => return,
+    };
+    let conn = conn.lock().expect("db connection mutex poisoned");
+
+    record_with(&conn, stats);
+}
+
+/// The most recent row at least one week older than `stats`, used as the
+/// comparison point for week-over-week deltas.
+fn week_ago_row_with(
+    conn: &Connection,
+    stats: &CachedStats,
+) -> rusqlite::Result<Option<CachedStats>> {
+    let cutoff = stats.updated_at as i64 - ONE_WEEK_SECS;
+
+    conn.query_row(
+        "SELECT member_count, member_count_since_send, unsubscribe_count_since_send,
+                avg_sub_rate, avg_unsub_rate, click_rate, timestamp
+         FROM stats_history
+         WHERE timestamp <= ?1
+         ORDER BY timestamp DESC
+         LIMIT 1",
+        params![cutoff],
+        |row| {
+            Ok(CachedStats {
+                member_count: row.get(0)?,
+                member_count_since_send: row.get(1)?,
+                unsubscribe_count_since_send: row.get(2)?,
+                avg_sub_rate: row.get(3)?,
+                avg_unsub_rate: row.get(4)?,
+                click_rate: row.get(5)?,
+                updated_at: row.get::<_, i64>(6)? as u64,
+            })
+        },
+    )
+    .optional()
+}
+
+fn week_ago_row(stats: &CachedStats) -> rusqlite::Result<Option<CachedStats>> {
+    let conn = match CONN.get() {
+        Some(conn) => conn,
+        None => return Ok(None),
+    };
+    let conn = conn.lock().expect("db connection mutex poisoned");
+
+    week_ago_row_with(&conn, stats)
+}
+
+/// Per-metric change versus the same metric ~7 days ago, where both the
+/// current and prior values are known.
+#[derive(Debug, Default)]
+pub struct Deltas {
+    pub member_count: Option<f64>,
+    pub member_count_since_send: Option<f64>,
+    pub unsubscribe_count_since_send: Option<f64>,
+    pub avg_sub_rate: Option<f64>,
+    pub avg_unsub_rate: Option<f64>,
+    pub click_rate: Option<f64>,
+}
+
+fn diff(current: Option<f64>, prior: Option<f64>) -> Option<f64> {
+    match (current, prior) {
+        (Some(c), Some(p)) => Some(c - p),
+        _ => None,
+    }
+}
+
+fn week_over_week_from(stats: &CachedStats, prior: Option<CachedStats>) -> Deltas {
+    match prior {
+        Some(prior) => Deltas {
+            member_count: diff(stats.member_count, prior.member_count),
+            member_count_since_send: diff(
+                stats.member_count_since_send,
+                prior.member_count_since_send,
+            ),
+            unsubscribe_count_since_send: diff(
+                stats.unsubscribe_count_since_send,
+                prior.unsubscribe_count_since_send,
+            ),
+            avg_sub_rate: diff(stats.avg_sub_rate, prior.avg_sub_rate),
+            avg_unsub_rate: diff(stats.avg_unsub_rate, prior.avg_unsub_rate),
+            click_rate: diff(stats.click_rate, prior.click_rate),
+        },
+        None => Deltas::default(),
+    }
+}
+
+/// Compute week-over-week deltas for `stats`. Returns all-`None` deltas
+/// if there's no row far enough back yet (e.g. the bot hasn't been
+/// running for a week).
+pub fn week_over_week(stats: &CachedStats) -> Deltas {
+    let prior = match week_ago_row(stats) {
+        Ok(row) => row,
+        Err(e) => {
+            error!("Error looking up week-ago stats: {}", e);
+            None
+        }
+    };
+
+    week_over_week_from(stats, prior)
+}
+
+/// Render a delta as a trend suffix, e.g. " (▲ +48 vs last week)", or an
+/// empty string if there's nothing to compare against.
+pub fn trend(delta: Option<f64>) -> String {
+    match delta {
+        Some(d) if d > 0.0 => format!(" (\u{25b2} +{:.0} vs last week)", d),
+        Some(d) if d < 0.0 => format!(" (\u{25bc} {:.0} vs last week)", d),
+        Some(_) => String::from(" (\u{2014} no change vs last week)"),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stats_at(updated_at: u64, member_count: f64) -> CachedStats {
+        CachedStats {
+            member_count: Some(member_count),
+            member_count_since_send: None,
+            unsubscribe_count_since_send: None,
+            avg_sub_rate: None,
+            avg_unsub_rate: None,
+            click_rate: None,
+            updated_at,
+        }
+    }
+
+    #[test]
+    fn week_over_week_reports_trend_across_eight_days() {
+        // An isolated in-memory connection, not the process-wide CONN
+        // singleton, so this test doesn't race other tests over init()
+        // and doesn't leave a totorobot.db file behind in the repo.
+        let conn = Connection::open_in_memory().expect("in-memory sqlite connection");
+        create_schema(&conn).expect("schema creation");
+
+        let eight_days_ago = 1_700_000_000;
+        let now = eight_days_ago + 8 * 24 * 60 * 60;
+
+        record_with(&conn, &stats_at(eight_days_ago, 1000.0));
+
+        let prior = week_ago_row_with(&conn, &stats_at(now, 1048.0)).expect("query");
+        let deltas = week_over_week_from(&stats_at(now, 1048.0), prior);
+
+        assert_eq!(deltas.member_count, Some(48.0));
+        assert_eq!(trend(deltas.member_count), " (\u{25b2} +48 vs last week)");
+    }
+}
@@ -9,15 +9,21 @@ extern crate rocket;
 extern crate rocket_contrib;
 
 use log::info;
-use mailchimp::{Lists, MailchimpApi};
-use rocket::config::{Config, Environment};
+use rocket::config::{Config as RocketConfig, Environment};
 use rocket::http::{ContentType, Status};
 use rocket::request::Request;
 use rocket::response;
 use rocket::response::{Responder, Response};
+use rocket::State;
 use rocket_contrib::json::JsonValue;
-use std::collections::HashMap;
-use std::env;
+
+mod campaign_report;
+mod config;
+mod db;
+mod notifier;
+mod stats;
+
+use config::Config;
 
 // AzureResponse
 // Function responses are formatted as key/value pairs
@@ -55,118 +61,65 @@ impl<'r> Responder<'r> for ApiResponse {
 }
 
 #[get("/health_check")]
-fn health_check() -> &'static str {
-    "OK"
+fn health_check(config: State<Config>) -> ApiResponse {
+    ApiResponse {
+        json: json!({
+            "status": "OK",
+            "stats_cache_stale": stats::is_stale(&config),
+        }),
+        status: Status::Ok,
+    }
+}
+
+#[get("/stats")]
+fn get_stats(config: State<Config>) -> ApiResponse {
+    match stats::CACHE
+        .lock()
+        .expect("stats cache mutex poisoned")
+        .clone()
+    {
+        Some(cached) => ApiResponse {
+            json: json!({
+                "member_count": cached.member_count,
+                "member_count_since_send": cached.member_count_since_send,
+                "unsubscribe_count_since_send": cached.unsubscribe_count_since_send,
+                "avg_sub_rate": cached.avg_sub_rate,
+                "avg_unsub_rate": cached.avg_unsub_rate,
+                "click_rate": cached.click_rate,
+                "updated_at": cached.updated_at,
+                "stale": stats::is_stale(&config),
+            }),
+            status: Status::Ok,
+        },
+        None => ApiResponse {
+            json: json!({ "error": "stats not yet available" }),
+            status: Status::ServiceUnavailable,
+        },
+    }
 }
 
 #[post("/post_mailchimp_stats", format = "json")]
-fn post_mailchimp_stats() -> ApiResponse {
+fn post_mailchimp_stats(config: State<Config>) -> ApiResponse {
     let mut logs = Vec::new();
 
-    // Create API client
-    let api_key = env::var("TOTORO_MAILCHIMP_APIKEY")
-        .expect("TOTORO_MAILCHIMP_APIKEY not set");
-    let api = MailchimpApi::new(&api_key);
-
-    // Query the specific list
-    let lists = Lists::new(api);
-    let list_id = env::var("TOTORO_MAILCHIMP_LIST_ID")
-        .expect("TOTORO_MAILCHIMP_LIST_ID not set");
-    let r_list = lists.get_list_info(&list_id, HashMap::new());
-
-    match r_list {
-        Ok(list) => {
-            // Get the stats
-            let stats = list.stats.as_ref().expect("No stats returned");
-
-            info!("Raw stats: {:?}", stats);
-
-            // Construct the Campfire bot text
-            let mut content =
-                String::from("<strong>Mailchimp Stats (Rust)</strong><ul>");
-
-            // The number of active members in the list
-            if let Some(member_count) = stats.member_count {
-                let s = format!(
-                    "<li><strong>Active subscribers:</strong> {:.0}</li>",
-                    member_count
-                );
-                content.push_str(&s);
-            }
-
-            // The number of members who have subscribed since the last
-            // campaign was sent
-            if let Some(subscribe_count_since_send) =
-                stats.member_count_since_send
-            {
-                let s = format!(
-                    "<li><strong>Subscribes since last send:</strong> {:.0}</li>",
-                    subscribe_count_since_send
-                );
-                content.push_str(&s);
-            }
-
-            // The number of members who have unsubscribed since the last
-            // campaign was sent
-            if let Some(unsubscribe_count_since_send) =
-                stats.unsubscribe_count_since_send
-            {
-                let s = format!(
-                    "<li><strong>Unsubscribes since last send:</strong> {:.0}</li>",
-                    unsubscribe_count_since_send
-                );
-                content.push_str(&s);
-            }
-
-            // The average number of subscriptions per month for the list
-            if let Some(avg_sub_rate) = stats.avg_sub_rate {
-                let s = format!(
-                    "<li><strong>Subscribe rate:</strong> {:.0}/m</li>",
-                    avg_sub_rate
-                );
-                content.push_str(&s);
-            }
-
-            // The average number of unsubscriptions per month for the list
-            if let Some(avg_unsub_rate) = stats.avg_unsub_rate {
-                let s = format!(
-                    "<li><strong>Unsubscribe rate:</strong> {:.0}/m</li>",
-                    avg_unsub_rate
-                );
-                content.push_str(&s);
-            }
-
-            // The average click rate (a percentage represented as a number
-            // between 0 and 100) per campaign for the list
-            if let Some(click_rate) = stats.click_rate {
-                let s = format!(
-                    "<li><strong>Click rate:</strong> {:.0}%</li>",
-                    click_rate
-                );
-                content.push_str(&s);
-            }
-
-            // Only post to Basecamp if we are actually in production
-            if env::var("TOTORO_PRODUCTION").is_ok() {
-                // Send it over to Basecamp
-                // https://github.com/basecamp/bc3-api/blob/master/sections/chatbots.md#create-a-line
-                info!("Sending Basecamp: {:?}", content);
-
-                let basecamp_bot_url = env::var("TOTORO_BASECAMP_BOTURL")
-                    .expect("TOTORO_BASECAMP_BOTURL not set");
+    let cached = stats::CACHE
+        .lock()
+        .expect("stats cache mutex poisoned")
+        .clone();
 
-                let mut json_body = HashMap::new();
-                json_body.insert("content", content);
+    match cached {
+        Some(cached) => {
+            // Only notify chat if we are actually in production
+            if config.production {
+                let notifiers = notifier::build_notifiers(&config);
 
-                // Use blocking because rocket is itself blocking
-                let client = reqwest::blocking::Client::new();
-                let resp = client
-                    .post(&basecamp_bot_url)
-                    .json(&json_body)
-                    .send()
-                    .expect("Reqwest client error");
+                for n in &notifiers {
+                    if let Err(e) = n.send(&cached) {
+                        logs.push(format!("Error sending notification: {}", e));
+                    }
+                }
 
-                if resp.status().is_success() {
+                if logs.is_empty() {
                     // Build response JSON
                     let response = AzureResponse {
                         logs,
@@ -179,13 +132,6 @@ fn post_mailchimp_stats() -> ApiResponse {
                         status: Status::Ok,
                     }
                 } else {
-                    // Log errors
-                    let error = format!(
-                        "Error posting to Basecamp: {:?}",
-                        resp.status()
-                    );
-                    logs.push(error);
-
                     // Build response JSON
                     let response = AzureResponse {
                         logs,
@@ -199,7 +145,7 @@ fn post_mailchimp_stats() -> ApiResponse {
                     }
                 }
             } else {
-                info!("Would have posted to Basecamp: {:?}", content);
+                info!("Would have sent notifications for stats: {:?}", cached);
 
                 // Build response JSON
                 let response = AzureResponse {
@@ -214,9 +160,9 @@ fn post_mailchimp_stats() -> ApiResponse {
                 }
             }
         }
-        Err(e) => {
+        None => {
             // Log errors
-            let error = format!("Error getting Mailchimp list info: {:?}", e);
+            let error = String::from("No cached Mailchimp stats available yet");
             logs.push(error);
 
             // Build response JSON
@@ -234,35 +180,102 @@ fn post_mailchimp_stats() -> ApiResponse {
     }
 }
 
+#[post("/post_campaign_report", format = "json")]
+fn post_campaign_report(config: State<Config>) -> ApiResponse {
+    let mut logs = Vec::new();
+
+    match campaign_report::fetch_latest(&config) {
+        Ok(report) => {
+            // Only notify chat if we are actually in production
+            if config.production {
+                let notifiers = notifier::build_notifiers(&config);
+
+                for n in &notifiers {
+                    if let Err(e) = n.send_campaign_report(&report) {
+                        logs.push(format!("Error sending campaign report: {}", e));
+                    }
+                }
+            } else {
+                info!("Would have sent campaign report: {:?}", report);
+            }
+
+            let return_value = if logs.is_empty() { "ok" } else { "error" };
+            let status = if logs.is_empty() {
+                Status::Ok
+            } else {
+                Status::InternalServerError
+            };
+
+            // Build response JSON
+            let response = AzureResponse {
+                logs,
+                return_value: String::from(return_value),
+            };
+
+            // Return response
+            ApiResponse {
+                json: response.to_json(),
+                status,
+            }
+        }
+        Err(e) => {
+            // Log errors
+            logs.push(e);
+
+            // Build response JSON
+            let response = AzureResponse {
+                logs,
+                return_value: String::from("error"),
+            };
+
+            // Return response
+            ApiResponse {
+                json: response.to_json(),
+                status: Status::InternalServerError,
+            }
+        }
+    }
+}
+
 pub fn rocket() -> rocket::Rocket {
-    // Define Rocket routes
-    let routes = routes![health_check, post_mailchimp_stats,];
+    // Validate configuration up front so a missing or invalid variable
+    // fails fast at startup instead of panicking mid-request.
+    let config = Config::load().unwrap_or_else(|e| panic!("{}", e));
 
-    // Pick up custom port setting for Azure
-    // https://docs.microsoft.com/en-us/azure/azure-functions/create-first-function-vs-code-other?tabs=rust%2Clinux#create-and-build-your-function
-    let port: u16 = match env::var("FUNCTIONS_CUSTOMHANDLER_PORT") {
-        Ok(val) => val.parse().expect("Custom Handler port is not a number!"),
-        Err(_) => 3000,
-    };
+    db::init(&config.db_path);
+
+    // Start polling Mailchimp in the background so handlers can read a
+    // warm cache instead of blocking on the API per request.
+    stats::start_poller(config.clone());
+
+    // Define Rocket routes
+    let routes = routes![
+        health_check,
+        post_mailchimp_stats,
+        get_stats,
+        post_campaign_report,
+    ];
 
     // Creating a custom config for each environment seems to be the only way
     // to set a custom port on Rocket
     // https://api.rocket.rs/v0.4/rocket/config/struct.ConfigBuilder.html#example-2
-    let config;
-    if env::var("TOTORO_PRODUCTION").is_ok() {
-        config = Config::build(Environment::Production)
-            .port(port)
+    let rocket_config;
+    if config.production {
+        rocket_config = RocketConfig::build(Environment::Production)
+            .port(config.port)
             .log_level(rocket::config::LoggingLevel::Normal)
             .unwrap();
     } else {
-        config = Config::build(Environment::Development)
+        rocket_config = RocketConfig::build(Environment::Development)
             .address("127.0.0.1")
-            .port(port)
+            .port(config.port)
             .log_level(rocket::config::LoggingLevel::Debug)
             .unwrap();
     }
 
-    rocket::custom(config).mount("/", routes)
+    rocket::custom(rocket_config)
+        .manage(config)
+        .mount("/", routes)
 }
 
 #[cfg(test)]
@@ -273,11 +286,20 @@ mod test {
 
     #[test]
     fn health_check_ok() {
+        // Config::load() requires these to be set; the values themselves
+        // are never used since the poller just logs and retries on a
+        // failed Mailchimp request.
+        std::env::set_var("TOTORO_MAILCHIMP_APIKEY", "test-apikey");
+        std::env::set_var("TOTORO_MAILCHIMP_LIST_ID", "test-list-id");
+        std::env::set_var("TOTORO_BASECAMP_BOTURL", "https://example.com/test-bot");
+
         let client = Client::new(rocket()).expect("valid rocket instance");
         let mut response = client.get("/health_check").dispatch();
         assert_eq!(response.status(), Status::Ok);
-        assert_eq!(response.body_string(), Some("OK".into()));
+        let body = response.body_string().expect("response body");
+        assert!(body.contains("\"status\":\"OK\""));
     }
 
     // TODO: Test post_mailchimp_stats
+    // TODO: Test /stats
 }
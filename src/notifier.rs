@@ -0,0 +1,316 @@
+// Copyright 2021, Console Ltd https://console.dev
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+// Chat-notification backends.
+//
+// Posting used to be hard-wired to the Basecamp chatbot endpoint. The
+// `Notifier` trait decouples stats-gathering from the destination: each
+// implementation owns its own message formatting and delivery, and
+// `build_notifiers` picks one (or several, fanned out) based on
+// `Config::notifiers`.
+
+use crate::campaign_report::CampaignReport;
+use crate::config::Config;
+use crate::stats::CachedStats;
+use log::info;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Request(reqwest::Error),
+    Status(reqwest::StatusCode),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotifyError::Request(e) => write!(f, "request error: {}", e),
+            NotifyError::Status(status) => write!(f, "unsuccessful response: {}", status),
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+impl From<reqwest::Error> for NotifyError {
+    fn from(e: reqwest::Error) -> Self {
+        NotifyError::Request(e)
+    }
+}
+
+/// Something that can deliver a stats snapshot to a chat destination.
+pub trait Notifier {
+    fn send(&self, stats: &CachedStats) -> Result<(), NotifyError>;
+
+    fn send_campaign_report(&self, report: &CampaignReport) -> Result<(), NotifyError>;
+}
+
+fn post_json(url: &str, body: &HashMap<&str, String>) -> Result<(), NotifyError> {
+    // Use blocking because rocket is itself blocking
+    let client = reqwest::blocking::Client::new();
+    let resp = client.post(url).json(body).send()?;
+
+    if resp.status().is_success() {
+        Ok(())
+    } else {
+        Err(NotifyError::Status(resp.status()))
+    }
+}
+
+// One line of a chat message: a label and its already-formatted value
+// (including any unit, like "%" or "/m") plus a trailing trend suffix.
+// Built once per message type and rendered per-backend, instead of each
+// backend re-walking every `Option` field itself.
+type Line = (&'static str, String, String);
+
+/// The list-stats lines, in display order. Skips any metric Mailchimp
+/// didn't return.
+fn stats_lines(stats: &CachedStats) -> Vec<Line> {
+    let deltas = crate::db::week_over_week(stats);
+    let mut lines = Vec::new();
+
+    if let Some(v) = stats.member_count {
+        lines.push((
+            "Active subscribers",
+            format!("{:.0}", v),
+            crate::db::trend(deltas.member_count),
+        ));
+    }
+    if let Some(v) = stats.member_count_since_send {
+        lines.push((
+            "Subscribes since last send",
+            format!("{:.0}", v),
+            crate::db::trend(deltas.member_count_since_send),
+        ));
+    }
+    if let Some(v) = stats.unsubscribe_count_since_send {
+        lines.push((
+            "Unsubscribes since last send",
+            format!("{:.0}", v),
+            crate::db::trend(deltas.unsubscribe_count_since_send),
+        ));
+    }
+    if let Some(v) = stats.avg_sub_rate {
+        lines.push((
+            "Subscribe rate",
+            format!("{:.0}/m", v),
+            crate::db::trend(deltas.avg_sub_rate),
+        ));
+    }
+    if let Some(v) = stats.avg_unsub_rate {
+        lines.push((
+            "Unsubscribe rate",
+            format!("{:.0}/m", v),
+            crate::db::trend(deltas.avg_unsub_rate),
+        ));
+    }
+    if let Some(v) = stats.click_rate {
+        lines.push((
+            "Click rate",
+            format!("{:.0}%", v),
+            crate::db::trend(deltas.click_rate),
+        ));
+    }
+
+    lines
+}
+
+/// The campaign-report lines, in display order. No trend suffix since
+/// there's nothing to compare a single campaign's report against.
+fn campaign_report_lines(report: &CampaignReport) -> Vec<Line> {
+    let mut lines = Vec::new();
+
+    if let Some(v) = report.opens {
+        lines.push(("Opens", format!("{:.0}", v), String::new()));
+    }
+    if let Some(v) = report.unique_opens {
+        lines.push(("Unique opens", format!("{:.0}", v), String::new()));
+    }
+    if let Some(v) = report.open_rate {
+        lines.push(("Open rate", format!("{:.0}%", v * 100.0), String::new()));
+    }
+    if let Some(v) = report.clicks {
+        lines.push(("Clicks", format!("{:.0}", v), String::new()));
+    }
+    if let Some(v) = report.click_rate {
+        lines.push(("Click rate", format!("{:.0}%", v * 100.0), String::new()));
+    }
+    if let Some(v) = report.bounces {
+        lines.push(("Bounces", format!("{:.0}", v), String::new()));
+    }
+    if let Some(v) = report.unsubscribes {
+        lines.push(("Unsubscribes", format!("{:.0}", v), String::new()));
+    }
+
+    lines
+}
+
+/// Render `lines` as the Basecamp chatbot's HTML `<ul>` markup.
+fn render_html(title: &str, lines: &[Line]) -> String {
+    let mut content = format!("<strong>{}</strong><ul>", title);
+    for (label, value, suffix) in lines {
+        content.push_str(&format!(
+            "<li><strong>{}:</strong> {}{}</li>",
+            label, value, suffix
+        ));
+    }
+    content
+}
+
+/// Render `lines` as a newline-joined markdown message, using `bold` as
+/// the emphasis marker (`*` for Slack, `**` for Discord).
+fn render_markdown(title: &str, lines: &[Line], bold: &str) -> String {
+    let mut rendered = vec![format!("{bold}{}{bold}", title, bold = bold)];
+    for (label, value, suffix) in lines {
+        rendered.push(format!(
+            "{bold}{}:{bold} {}{}",
+            label,
+            bold = bold,
+            value = value,
+            suffix = suffix
+        ));
+    }
+    rendered.join("\n")
+}
+
+/// Basecamp chatbot "create a line" endpoint. Emits the original HTML
+/// `<ul>` markup.
+/// https://github.com/basecamp/bc3-api/blob/master/sections/chatbots.md#create-a-line
+pub struct Basecamp {
+    bot_url: String,
+}
+
+impl Basecamp {
+    pub fn new(bot_url: String) -> Self {
+        Basecamp { bot_url }
+    }
+}
+
+impl Notifier for Basecamp {
+    fn send(&self, stats: &CachedStats) -> Result<(), NotifyError> {
+        let content = render_html("Mailchimp Stats (Rust)", &stats_lines(stats));
+
+        info!("Sending Basecamp: {:?}", content);
+
+        let mut body = HashMap::new();
+        body.insert("content", content);
+
+        post_json(&self.bot_url, &body)
+    }
+
+    fn send_campaign_report(&self, report: &CampaignReport) -> Result<(), NotifyError> {
+        let title = format!("Campaign Report: {} ({})", report.subject, report.send_time);
+        let content = render_html(&title, &campaign_report_lines(report));
+
+        info!("Sending Basecamp campaign report: {:?}", content);
+
+        let mut body = HashMap::new();
+        body.insert("content", content);
+
+        post_json(&self.bot_url, &body)
+    }
+}
+
+/// A Slack incoming webhook. Emits Slack's `mrkdwn` formatting.
+/// https://api.slack.com/messaging/webhooks
+pub struct Slack {
+    webhook_url: String,
+}
+
+impl Slack {
+    pub fn new(webhook_url: String) -> Self {
+        Slack { webhook_url }
+    }
+}
+
+impl Notifier for Slack {
+    fn send(&self, stats: &CachedStats) -> Result<(), NotifyError> {
+        let text = render_markdown("Mailchimp Stats (Rust)", &stats_lines(stats), "*");
+
+        info!("Sending Slack: {:?}", text);
+
+        let mut body = HashMap::new();
+        body.insert("text", text);
+
+        post_json(&self.webhook_url, &body)
+    }
+
+    fn send_campaign_report(&self, report: &CampaignReport) -> Result<(), NotifyError> {
+        let title = format!("Campaign Report: {} ({})", report.subject, report.send_time);
+        let text = render_markdown(&title, &campaign_report_lines(report), "*");
+
+        info!("Sending Slack campaign report: {:?}", text);
+
+        let mut body = HashMap::new();
+        body.insert("text", text);
+
+        post_json(&self.webhook_url, &body)
+    }
+}
+
+/// A Discord incoming webhook. Emits Discord markdown.
+/// https://discord.com/developers/docs/resources/webhook#execute-webhook
+pub struct Discord {
+    webhook_url: String,
+}
+
+impl Discord {
+    pub fn new(webhook_url: String) -> Self {
+        Discord { webhook_url }
+    }
+}
+
+impl Notifier for Discord {
+    fn send(&self, stats: &CachedStats) -> Result<(), NotifyError> {
+        let content = render_markdown("Mailchimp Stats (Rust)", &stats_lines(stats), "**");
+
+        info!("Sending Discord: {:?}", content);
+
+        let mut body = HashMap::new();
+        body.insert("content", content);
+
+        post_json(&self.webhook_url, &body)
+    }
+
+    fn send_campaign_report(&self, report: &CampaignReport) -> Result<(), NotifyError> {
+        let title = format!("Campaign Report: {} ({})", report.subject, report.send_time);
+        let content = render_markdown(&title, &campaign_report_lines(report), "**");
+
+        info!("Sending Discord campaign report: {:?}", content);
+
+        let mut body = HashMap::new();
+        body.insert("content", content);
+
+        post_json(&self.webhook_url, &body)
+    }
+}
+
+/// Build the set of notifiers selected via `config.notifiers`. `Config`
+/// validation already guarantees each selected target has the
+/// configuration it needs, so unlike the old `TOTORO_NOTIFIER` parsing
+/// this can't fail.
+pub fn build_notifiers(config: &Config) -> Vec<Box<dyn Notifier>> {
+    config
+        .notifiers
+        .iter()
+        .filter_map(|target| match target.as_str() {
+            "basecamp" => config
+                .basecamp_boturl
+                .clone()
+                .map(|url| Box::new(Basecamp::new(url)) as Box<dyn Notifier>),
+            "slack" => config
+                .slack_webhook_url
+                .clone()
+                .map(|url| Box::new(Slack::new(url)) as Box<dyn Notifier>),
+            "discord" => config
+                .discord_webhook_url
+                .clone()
+                .map(|url| Box::new(Discord::new(url)) as Box<dyn Notifier>),
+            other => {
+                log::error!("Unknown notifier target: {:?}", other);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,99 @@
+// Copyright 2021, Console Ltd https://console.dev
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+// Background polling of Mailchimp list stats.
+//
+// The Azure timer trigger that drives `post_mailchimp_stats` needs to
+// respond quickly, so we don't want it blocking on a Mailchimp API call
+// every time it fires. Instead a background thread polls Mailchimp on a
+// fixed interval and stores the latest snapshot here; handlers just read
+// whatever is cached.
+
+use crate::config::Config;
+use log::{error, info};
+use mailchimp::{Lists, MailchimpApi};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A snapshot of the list stats we care about, plus when we fetched it.
+#[derive(Debug, Clone)]
+pub struct CachedStats {
+    pub member_count: Option<f64>,
+    pub member_count_since_send: Option<f64>,
+    pub unsubscribe_count_since_send: Option<f64>,
+    pub avg_sub_rate: Option<f64>,
+    pub avg_unsub_rate: Option<f64>,
+    pub click_rate: Option<f64>,
+    pub updated_at: u64,
+}
+
+pub static CACHE: Lazy<Mutex<Option<CachedStats>>> = Lazy::new(|| Mutex::new(None));
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before UNIX_EPOCH")
+        .as_secs()
+}
+
+/// Fetch the list stats from Mailchimp once and store them in `CACHE`.
+pub fn refresh(config: &Config) {
+    let api = MailchimpApi::new(&config.mailchimp_apikey);
+    let lists = Lists::new(api);
+
+    match lists.get_list_info(&config.mailchimp_list_id, HashMap::new()) {
+        Ok(list) => {
+            let stats = match list.stats.as_ref() {
+                Some(stats) => stats,
+                None => {
+                    error!("Mailchimp list info response had no stats");
+                    return;
+                }
+            };
+
+            info!("Polled Mailchimp stats: {:?}", stats);
+
+            let cached = CachedStats {
+                member_count: stats.member_count,
+                member_count_since_send: stats.member_count_since_send,
+                unsubscribe_count_since_send: stats.unsubscribe_count_since_send,
+                avg_sub_rate: stats.avg_sub_rate,
+                avg_unsub_rate: stats.avg_unsub_rate,
+                click_rate: stats.click_rate,
+                updated_at: now(),
+            };
+
+            crate::db::record(&cached);
+
+            *CACHE.lock().expect("stats cache mutex poisoned") = Some(cached);
+        }
+        Err(e) => {
+            error!("Error polling Mailchimp list info: {:?}", e);
+        }
+    }
+}
+
+/// Spawn the background poller thread. Fetches immediately so the cache
+/// is warm as soon as possible, then repeats every `config.poll_interval`
+/// seconds.
+pub fn start_poller(config: Config) {
+    thread::spawn(move || {
+        let interval = Duration::from_secs(config.poll_interval);
+        loop {
+            refresh(&config);
+            thread::sleep(interval);
+        }
+    });
+}
+
+/// Returns `true` if the cache has no snapshot yet, or the snapshot is
+/// older than twice the poll interval.
+pub fn is_stale(config: &Config) -> bool {
+    match CACHE.lock().expect("stats cache mutex poisoned").as_ref() {
+        Some(cached) => now().saturating_sub(cached.updated_at) > config.poll_interval * 2,
+        None => true,
+    }
+}